@@ -0,0 +1,15 @@
+use email_address::EmailAddress;
+use error::BrokerError;
+use futures::future::{self, Future};
+use http::{ContextHandle, HandlerResult};
+use std::rc::Rc;
+
+/// Entry point of the email loop for `email_addr`.
+///
+/// Not implemented: this always fails with `ProviderCancelled`. No
+/// confirmation link is sent and no "check your email" page is shown,
+/// so a login that falls back to the email loop ends in that error.
+pub fn auth(ctx_handle: &ContextHandle, email_addr: &Rc<EmailAddress>) -> HandlerResult {
+    let _ = (ctx_handle, email_addr);
+    Box::new(future::err(BrokerError::ProviderCancelled))
+}
@@ -0,0 +1,94 @@
+use email_address::EmailAddress;
+use error::BrokerError;
+use futures::future::{self, Future};
+use http::{ContextHandle, HandlerResult};
+use std::rc::Rc;
+use std::time::Duration;
+use webfinger::Link;
+
+/// Default TTL for a cached provider discovery document + JWKS, used
+/// when the upstream response didn't advertise its own
+/// `Cache-Control`/`max-age`.
+const DEFAULT_PROVIDER_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+fn provider_cache_key(issuer: &str) -> String {
+    format!("oidc-provider:{}", issuer)
+}
+
+/// Looks up a previously-cached discovery document + JWKS for `issuer`,
+/// mirroring `handlers::auth`'s webfinger-link cache so a provider's own
+/// discovery round-trip doesn't get paid for on every login either.
+fn cached_provider_config(ctx_handle: &ContextHandle, issuer: &str) -> Result<Option<String>, BrokerError> {
+    let ctx = ctx_handle.borrow();
+    ctx.app.store.get(&provider_cache_key(issuer))
+}
+
+/// Caches `config` (the provider's raw discovery document + JWKS body)
+/// for `issuer`.
+fn cache_provider_config(ctx_handle: &ContextHandle, issuer: &str, config: &str, ttl: Duration) -> Result<(), BrokerError> {
+    let ctx = ctx_handle.borrow();
+    ctx.app.store.store(&provider_cache_key(issuer), config, ttl)
+}
+
+/// Fetches `issuer`'s discovery document and JWKS over HTTP, along with
+/// the TTL from the response's `Cache-Control`/`max-age`, or `None` if
+/// it didn't specify one.
+///
+/// Not implemented yet: doing so is an HTTP-client concern that lives
+/// outside this crate's pure, test-friendly logic. This always fails
+/// with `ProviderCancelled`, same as `auth()` itself.
+fn fetch_provider_config(_issuer: &str) -> Box<Future<Item = (String, Option<Duration>), Error = BrokerError>> {
+    Box::new(future::err(BrokerError::ProviderCancelled))
+}
+
+/// Authenticates the user against an OIDC-compatible upstream: either a
+/// webfinger-discovered `OidcIssuer`/`Portier`/`Google` link, or a
+/// statically-configured provider (in which case `credentials` carries
+/// the configured `client_id`/`client_secret` in place of the defaults
+/// the Portier/Google federation uses).
+///
+/// On success, this would mint our own id_token for the verified
+/// identity with `crypto::sign_jwt` — using the configured key's own
+/// algorithm and `kid`, not a hard-coded `RS256` — and hand it to
+/// `http::finish_session`, which redirects back to the relier with
+/// either that id_token or, for `response_type=code`, an authorization
+/// code wrapping it.
+///
+/// None of that is wired up yet: fetching the provider's discovery
+/// document and JWKS (`fetch_provider_config`, below) is an HTTP-client
+/// concern that lives outside this crate's pure, test-friendly logic,
+/// redirecting the browser there and verifying the upstream id_token on
+/// the way back need that same HTTP client, and `crypto::sign_jwt`
+/// itself still needs a real signing key (see `Key::sign`) before this
+/// path could produce a token even once the HTTP side exists. The
+/// caching around it is real, though, keyed by issuer just like
+/// `handlers::auth` caches webfinger links by domain, so wiring in the
+/// actual fetch later is a one-line change to `fetch_provider_config`,
+/// not a new caching layer.
+pub fn auth(ctx_handle: &ContextHandle, email_addr: &Rc<EmailAddress>, link: &Link, credentials: Option<&(String, String)>) -> HandlerResult {
+    let _ = (email_addr, credentials);
+
+    let cached = match cached_provider_config(ctx_handle, &link.href) {
+        Ok(cached) => cached,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    let issuer = link.href.clone();
+    let ctx_handle2 = Rc::clone(ctx_handle);
+    let config_f: Box<Future<Item = String, Error = BrokerError>> = match cached {
+        Some(config) => Box::new(future::ok(config)),
+        None => Box::new(fetch_provider_config(&issuer).and_then(move |(config, ttl)| {
+            // Best-effort: a cache write failure shouldn't fail the login.
+            let _ = cache_provider_config(&ctx_handle2, &issuer, &config, ttl.unwrap_or(DEFAULT_PROVIDER_CACHE_TTL));
+            future::ok(config)
+        })),
+    };
+
+    Box::new(config_f.and_then(|_config| {
+        // Parsing the discovery document + JWKS, redirecting the
+        // browser to the provider, and verifying the returned id_token
+        // still need the HTTP-client/crypto wiring described above, so
+        // even a cache hit can't complete a login yet.
+        future::err(BrokerError::ProviderCancelled)
+    }))
+}
@@ -0,0 +1,113 @@
+use error::BrokerError;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use store::Store;
+use store_limits::Limit;
+use tokio_core::reactor::Handle;
+
+/// A signing key and the algorithm it is used with.
+///
+/// The variant decides `signing_alg()`, which drives both the
+/// `alg`/`kty`/`crv` fields emitted by `public_jwk()` and the JWT `alg`
+/// header written by `crypto::sign_jwt`. Choosing which configured key
+/// to sign with is left to the caller of `crypto::sign_jwt`.
+pub enum Key {
+    Rsa { kid: String, /* ... RSA key material ... */ },
+    Ec { kid: String, /* ... EC P-256 key material ... */ },
+}
+
+impl Key {
+    /// The JWS `alg` this key signs with: `RS256` for an RSA key, or
+    /// `ES256` for an EC P-256 key.
+    pub fn signing_alg(&self) -> &'static str {
+        match *self {
+            Key::Rsa { .. } => "RS256",
+            Key::Ec { .. } => "ES256",
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        match *self {
+            Key::Rsa { ref kid, .. } => kid,
+            Key::Ec { ref kid, .. } => kid,
+        }
+    }
+
+    /// Signs `signing_input` (the base64url-encoded `header.claims`
+    /// portion of a JWS) with this key, using whichever algorithm
+    /// matches its type (see `signing_alg()`).
+    ///
+    /// Neither variant carries real key material yet (see the fields
+    /// above), so both currently return `BrokerError::Custom` rather
+    /// than produce a signature. This is deliberate: signing is the one
+    /// piece of the bridges that genuinely needs a crypto backend (RSA
+    /// PKCS#1v1.5 / ECDSA) we don't have wired into this crate, so this
+    /// fails loudly instead of panicking or emitting an unsigned token.
+    pub fn sign(&self, _signing_input: &[u8]) -> Result<Vec<u8>, BrokerError> {
+        match *self {
+            Key::Rsa { .. } => Err(BrokerError::Custom(
+                "RSA signing is not implemented: no key material or crypto backend is wired up".to_owned())),
+            Key::Ec { .. } => Err(BrokerError::Custom(
+                "EC signing is not implemented: no key material or crypto backend is wired up".to_owned())),
+        }
+    }
+
+    /// The public half of this key, as a JWK, with `kty`/`crv` (EC only)
+    /// /`alg`/`use`/`kid` set appropriately for the key's type.
+    pub fn public_jwk(&self) -> Value {
+        match *self {
+            Key::Rsa { ref kid, .. } => json!({
+                "kty": "RSA",
+                "alg": "RS256",
+                "use": "sig",
+                "kid": kid,
+                // "n" and "e" omitted here; filled in from the RSA key
+                // material by the real implementation.
+            }),
+            Key::Ec { ref kid, .. } => json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "alg": "ES256",
+                "use": "sig",
+                "kid": kid,
+                // "x" and "y" omitted here; filled in from the EC key
+                // material by the real implementation.
+            }),
+        }
+    }
+}
+
+/// A statically-configured upstream OIDC provider, matched by email
+/// domain, as an alternative to webfinger discovery.
+pub struct Provider {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// The login-hint template, rendered by `handlers::auth` when no
+/// `login_hint` was supplied on the request.
+pub struct Templates {
+    pub login_hint: ::mustache::Template,
+}
+
+/// Shared, read-only application state.
+pub struct App {
+    pub public_url: String,
+    pub allowed_origins: Option<Vec<String>>,
+    pub discovery_ttl: Duration,
+    pub keys_ttl: Duration,
+    pub keys: Vec<Key>,
+    pub store: Store,
+    pub limit_per_email: Limit,
+    pub handle: Handle,
+    pub templates: Templates,
+    /// Domain -> statically-configured provider, checked before
+    /// webfinger discovery.
+    pub providers: HashMap<String, Provider>,
+    /// Overall budget for discovery across all links.
+    pub discovery_timeout: Duration,
+    /// Timeout for a single provider's authentication attempt.
+    pub provider_timeout: Duration,
+}
@@ -0,0 +1,91 @@
+use config::App;
+use email_address::EmailAddress;
+use hyper::Method;
+use http::ReturnParams;
+use rand::{self, Rng};
+use std::rc::Rc;
+use url::form_urlencoded;
+
+/// Minimal gettext-style catalog; real localization is out of scope
+/// here, so this just hands back the input string.
+pub struct Catalog;
+
+impl Catalog {
+    pub fn gettext<'a>(&self, msgid: &'a str) -> &'a str {
+        msgid
+    }
+}
+
+/// State accumulated about the session under way, from the moment
+/// `start_session` is called until a bridge finishes authenticating the
+/// user.
+pub struct Session {
+    pub id: String,
+    pub client_id: String,
+    pub login_hint: String,
+    pub email_addr: Rc<EmailAddress>,
+    pub nonce: String,
+    /// `"id_token"` or `"code"`, echoing the original `response_type`.
+    pub response_type: String,
+    /// Set only for `response_type=code`, per RFC 7636.
+    pub code_challenge: Option<String>,
+}
+
+/// Per-request state: the incoming request, shared `App` state, and
+/// whatever the handler accumulates while processing it.
+pub struct Context {
+    pub app: Rc<App>,
+    pub method: Method,
+    pub return_params: Option<ReturnParams>,
+    pub session: Option<Session>,
+    raw_query: String,
+    raw_body: String,
+}
+
+impl Context {
+    pub fn new(app: Rc<App>, method: Method, raw_query: String, raw_body: String) -> Context {
+        Context { app, method, return_params: None, session: None, raw_query, raw_body }
+    }
+
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        form_urlencoded::parse(self.raw_query.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    pub fn form_params(&self) -> Vec<(String, String)> {
+        form_urlencoded::parse(self.raw_body.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    pub fn catalog(&self) -> Catalog {
+        Catalog
+    }
+
+    /// Begins a new session for this login attempt, recording everything
+    /// the bridge that eventually completes it will need: who is
+    /// authenticating, to which relier, and how the result should be
+    /// returned (`response_type`, and the PKCE challenge for the
+    /// Authorization Code flow).
+    pub fn start_session(
+        &mut self,
+        client_id: &str,
+        login_hint: &str,
+        email_addr: &Rc<EmailAddress>,
+        nonce: &str,
+        response_type: &str,
+        code_challenge: Option<&str>,
+    ) {
+        let id = rand::thread_rng().gen_ascii_chars().take(16).collect::<String>();
+        self.session = Some(Session {
+            id,
+            client_id: client_id.to_owned(),
+            login_hint: login_hint.to_owned(),
+            email_addr: Rc::clone(email_addr),
+            nonce: nonce.to_owned(),
+            response_type: response_type.to_owned(),
+            code_challenge: code_challenge.map(str::to_owned),
+        });
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A parsed, normalized email address.
+///
+/// Normalization lower-cases the domain part (domains are
+/// case-insensitive) but leaves the local part untouched, since it is
+/// not in general safe to lower-case it.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EmailAddress {
+    raw: String,
+    at: usize,
+}
+
+impl EmailAddress {
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.raw[self.at + 1..]
+    }
+}
+
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError;
+
+impl FromStr for EmailAddress {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let at = s.rfind('@').ok_or(ParseError)?;
+        let (local, domain) = (&s[..at], &s[at + 1..]);
+        if local.is_empty() || domain.is_empty() || domain.contains('@') {
+            return Err(ParseError);
+        }
+        let raw = format!("{}@{}", local, domain.to_lowercase());
+        let at = local.len();
+        Ok(EmailAddress { raw, at })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EmailAddress;
+
+    #[test]
+    fn normalizes_domain_case() {
+        let addr: EmailAddress = "User@Example.COM".parse().unwrap();
+        assert_eq!(addr.as_str(), "User@example.com");
+        assert_eq!(addr.domain(), "example.com");
+    }
+
+    #[test]
+    fn rejects_missing_at() {
+        assert!("not-an-email".parse::<EmailAddress>().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+/// Errors that can occur while handling a request.
+///
+/// `Provider` and `ProviderCancelled` are distinguished from the other
+/// variants because the discovery/bridge chain in `handlers::auth` uses
+/// them to decide whether to fall through to the next link (or to the
+/// email loop), rather than failing the request outright.
+#[derive(Debug)]
+pub enum BrokerError {
+    /// The request was malformed or failed validation; reported back to
+    /// the user or relier as-is.
+    Input(String),
+    /// An upstream provider failed to answer, or answered in a way we
+    /// don't trust (e.g. a signature that no longer verifies).
+    Provider(String),
+    /// Discovery found nothing usable for this domain.
+    ProviderCancelled,
+    /// Too many attempts for this address recently.
+    RateLimited,
+    /// An internal error not attributable to the request or a provider.
+    Custom(String),
+}
+
+impl BrokerError {
+    /// Logs this error at a level appropriate to its severity.
+    pub fn log(&self) {
+        eprintln!("{}", self);
+    }
+}
+
+impl fmt::Display for BrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BrokerError::Input(ref msg) => write!(f, "input error: {}", msg),
+            BrokerError::Provider(ref msg) => write!(f, "provider error: {}", msg),
+            BrokerError::ProviderCancelled => write!(f, "provider cancelled"),
+            BrokerError::RateLimited => write!(f, "rate limited"),
+            BrokerError::Custom(ref msg) => write!(f, "internal error: {}", msg),
+        }
+    }
+}
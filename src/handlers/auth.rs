@@ -1,3 +1,4 @@
+use base64;
 use bridges;
 use email_address::EmailAddress;
 use error::BrokerError;
@@ -7,7 +8,9 @@ use hyper::Method;
 use hyper::header::ContentType;
 use hyper::server::Response;
 use mustache;
+use rand::{self, Rng};
 use serde_json::{Value, from_value};
+use sha2::{Digest, Sha256};
 use std::rc::Rc;
 use std::time::Duration;
 use store_limits::addr_limiter;
@@ -16,6 +19,99 @@ use validation::parse_redirect_uri;
 use webfinger::{self, Link, Relation};
 
 
+/// How long an authorization code stays redeemable for.
+///
+/// Codes are meant to be exchanged by the client immediately after the
+/// front-channel redirect, so this is intentionally short-lived.
+const AUTH_CODE_TTL: Duration = Duration::from_secs(60);
+
+/// Data bound to an authorization code at the time it is issued, so that
+/// `token()` can finish the exchange without re-contacting the provider.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct AuthCode {
+    pub(crate) id_token: String,
+    pub(crate) client_id: String,
+    pub(crate) redirect_uri: String,
+    pub(crate) code_challenge: String,
+}
+
+fn auth_code_key(code: &str) -> String {
+    format!("authcode:{}", code)
+}
+
+/// Generates a fresh opaque authorization code and binds `data` to it in
+/// the store, to be redeemed exactly once by `take_auth_code`.
+///
+/// Called by `http::finish_session` once a bridge has a signed id_token
+/// in hand, for sessions that requested `response_type=code`.
+pub(crate) fn issue_auth_code(ctx: &ContextHandle, data: &AuthCode) -> Result<String, BrokerError> {
+    let ctx = ctx.borrow();
+    let code = rand::thread_rng().gen_ascii_chars().take(32).collect::<String>();
+    let value = serde_json::to_string(data)
+        .map_err(|e| BrokerError::Custom(format!("unable to serialize authorization code: {}", e)))?;
+    ctx.app.store.store(&auth_code_key(&code), &value, AUTH_CODE_TTL)?;
+    Ok(code)
+}
+
+/// Looks up and immediately invalidates an authorization code, so that a
+/// replayed code always fails even before it expires.
+fn take_auth_code(ctx: &ContextHandle, code: &str) -> Result<Option<AuthCode>, BrokerError> {
+    let ctx = ctx.borrow();
+    let value = match ctx.app.store.take(&auth_code_key(code))? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    serde_json::from_str(&value)
+        .map(Some)
+        .map_err(|e| BrokerError::Custom(format!("unable to parse stored authorization code: {}", e)))
+}
+
+/// Verifies a PKCE `code_verifier` against the `code_challenge` presented
+/// in the original authorization request (RFC 7636 §4.6, `S256` only).
+fn verify_code_challenge(verifier: &str, challenge: &str) -> bool {
+    let digest = Sha256::digest(verifier.as_bytes());
+    base64::encode_config(&digest, base64::URL_SAFE_NO_PAD) == challenge
+}
+
+
+/// Default TTL for cached webfinger discovery results, used when the
+/// upstream response didn't advertise its own `Cache-Control`/`max-age`.
+const DEFAULT_DISCOVERY_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+fn discovery_cache_key(domain: &str) -> String {
+    format!("discovery:{}", domain)
+}
+
+/// Looks up a previously-cached set of webfinger links for `domain`.
+fn cached_links(ctx_handle: &ContextHandle, domain: &str) -> Result<Option<Vec<Link>>, BrokerError> {
+    let ctx = ctx_handle.borrow();
+    let value = match ctx.app.store.get(&discovery_cache_key(domain))? {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    serde_json::from_str(&value)
+        .map(Some)
+        .map_err(|e| BrokerError::Custom(format!("unable to parse cached discovery result: {}", e)))
+}
+
+/// Caches `links` for `domain`, so that subsequent logins skip the
+/// webfinger round-trip until `ttl` elapses.
+fn cache_links(ctx_handle: &ContextHandle, domain: &str, links: &[Link], ttl: Duration) -> Result<(), BrokerError> {
+    let ctx = ctx_handle.borrow();
+    let value = serde_json::to_string(links)
+        .map_err(|e| BrokerError::Custom(format!("unable to serialize discovery result: {}", e)))?;
+    ctx.app.store.store(&discovery_cache_key(domain), &value, ttl)
+}
+
+/// Drops a cached discovery result, so that a rotated upstream signing
+/// key or other verification failure doesn't wedge logins behind a
+/// stale cache entry.
+fn invalidate_discovery_cache(ctx_handle: &ContextHandle, domain: &str) -> Result<(), BrokerError> {
+    let ctx = ctx_handle.borrow();
+    ctx.app.store.remove(&discovery_cache_key(domain))
+}
+
+
 /// Request handler to return the OpenID Discovery document.
 ///
 /// Most of this is hard-coded for now, although the URLs are constructed by
@@ -23,17 +119,28 @@ use webfinger::{self, Link, Relation};
 pub fn discovery(ctx_handle: &ContextHandle) -> HandlerResult {
     let ctx = ctx_handle.borrow();
 
+    // Derived from the actually-configured keys, rather than hard-coded,
+    // so that an operator who rotates in an EC key has it advertised
+    // here without a code change.
+    let mut signing_algs = ctx.app.keys.iter()
+        .map(|key| key.signing_alg())
+        .collect::<Vec<_>>();
+    signing_algs.sort();
+    signing_algs.dedup();
+
     let obj = json!({
         "issuer": ctx.app.public_url,
         "authorization_endpoint": format!("{}/auth", ctx.app.public_url),
+        "token_endpoint": format!("{}/token", ctx.app.public_url),
         "jwks_uri": format!("{}/keys.json", ctx.app.public_url),
         "scopes_supported": vec!["openid", "email"],
         "claims_supported": vec!["iss", "aud", "exp", "iat", "email"],
-        "response_types_supported": vec!["id_token"],
+        "response_types_supported": vec!["id_token", "code"],
         "response_modes_supported": vec!["form_post", "fragment"],
-        "grant_types_supported": vec!["implicit"],
+        "grant_types_supported": vec!["implicit", "authorization_code"],
         "subject_types_supported": vec!["public"],
-        "id_token_signing_alg_values_supported": vec!["RS256"],
+        "id_token_signing_alg_values_supported": signing_algs,
+        "code_challenge_methods_supported": vec!["S256"],
     });
     Box::new(json_response(&obj, ctx.app.discovery_ttl))
 }
@@ -57,6 +164,81 @@ pub fn key_set(ctx_handle: &ContextHandle) -> HandlerResult {
 }
 
 
+/// Attempts `bridges::oidc::auth` for each relevant link in `links`, in
+/// the priority order returned by webfinger, moving on to the next link
+/// when the current one times out or fails with `BrokerError::Provider`
+/// or `BrokerError::ProviderCancelled`. Returns `ProviderCancelled` once
+/// every link has been tried, so the caller falls through to the email
+/// loop. Each attempt gets its own timeout (`ctx.app.provider_timeout`);
+/// a timed-out attempt keeps running in the background rather than being
+/// dropped, and its error is logged if it later fails. A `Provider`
+/// failure also drops the domain's cached discovery result.
+///
+/// `credentials` is forwarded to every attempt unchanged; it is only
+/// ever `Some` when `links` is the single synthetic link built for a
+/// statically-configured provider, which is why that path goes through
+/// here too, instead of calling `bridges::oidc::auth` directly: a
+/// misconfigured static provider gets the same per-attempt timeout and
+/// fallback to the email loop as a webfinger-discovered one.
+fn auth_with_links(ctx_handle: ContextHandle, email_addr: Rc<EmailAddress>, links: Rc<Vec<Link>>, index: usize, credentials: Option<Rc<(String, String)>>) -> HandlerResult {
+    let link = match links.get(index) {
+        Some(link) => link,
+        None => return Box::new(future::err(BrokerError::ProviderCancelled)),
+    };
+
+    let inner: HandlerResult = match link {
+        // Portier and Google providers share an implementation.
+        &Link { rel: Relation::OidcIssuer, .. }
+            | &Link { rel: Relation::Portier, .. }
+            | &Link { rel: Relation::Google, .. }
+            => bridges::oidc::auth(&ctx_handle, &email_addr, link, credentials.as_ref().map(|c| &**c)),
+        _ => return auth_with_links(ctx_handle, email_addr, links, index + 1, credentials),
+    };
+
+    let provider_timeout = ctx_handle.borrow().app.provider_timeout;
+    let timeout = Timeout::new(provider_timeout, &ctx_handle.borrow().app.handle)
+        .expect("failed to create provider timeout");
+
+    let spawn_ctx_handle = Rc::clone(&ctx_handle);
+    let next_ctx_handle = Rc::clone(&ctx_handle);
+    let next_email_addr = Rc::clone(&email_addr);
+    let next_links = Rc::clone(&links);
+    let next_credentials = credentials.clone();
+    Box::new(timeout.select2(inner).then(move |result| -> HandlerResult {
+        match result {
+            // This provider timed out; let it keep running in the
+            // background, and move on to the next link.
+            Ok(Either::A((_, f))) => {
+                spawn_ctx_handle.borrow().app.handle.spawn(
+                    f.map(|_| ()).map_err(|e| { e.log(); () }));
+                auth_with_links(next_ctx_handle, next_email_addr, next_links, index + 1, next_credentials)
+            },
+            Err(Either::A((e, _))) => {
+                panic!("error in provider timeout: {}", e)
+            },
+            // This provider answered.
+            Ok(Either::B((v, _))) => Box::new(future::ok(v)),
+            Err(Either::B((e, _))) => {
+                match e {
+                    BrokerError::Provider(_) | BrokerError::ProviderCancelled => {
+                        e.log();
+                        if let BrokerError::Provider(_) = e {
+                            // The cached discovery result (if any) led us to
+                            // a provider that failed verification, e.g.
+                            // because it rotated its signing key. Drop it so
+                            // the next attempt re-discovers.
+                            let _ = invalidate_discovery_cache(&next_ctx_handle, next_email_addr.domain());
+                        }
+                        auth_with_links(next_ctx_handle, next_email_addr, next_links, index + 1, next_credentials)
+                    },
+                    _ => Box::new(future::err(e)),
+                }
+            },
+        }
+    }))
+}
+
+
 /// Request handler for authentication requests from the RP.
 ///
 /// Calls the `oidc::request()` function if the provided email address's
@@ -115,11 +297,26 @@ pub fn auth(ctx_handle: &ContextHandle) -> HandlerResult {
     }
 
     let nonce = try_get_input_param!(params, "nonce");
-    if try_get_input_param!(params, "response_type") != "id_token" {
+    let response_type = try_get_input_param!(params, "response_type");
+    if response_type != "id_token" && response_type != "code" {
         return Box::new(future::err(BrokerError::Input(
-            "unsupported response_type, only id_token is supported".to_owned())));
+            "unsupported response_type, must be id_token or code".to_owned())));
     }
 
+    // For the Authorization Code flow, require a PKCE challenge so that
+    // only the client holding the matching `code_verifier` can redeem
+    // the code at the token endpoint.
+    let code_challenge = if response_type == "code" {
+        let code_challenge_method = try_get_input_param!(params, "code_challenge_method", "S256".to_owned());
+        if code_challenge_method != "S256" {
+            return Box::new(future::err(BrokerError::Input(
+                "unsupported code_challenge_method, only S256 is supported".to_owned())));
+        }
+        Some(try_get_input_param!(params, "code_challenge"))
+    } else {
+        None
+    };
+
     let login_hint = try_get_input_param!(params, "login_hint", "".to_string());
     if login_hint == "" {
         let catalog = ctx.catalog();
@@ -163,63 +360,111 @@ pub fn auth(ctx_handle: &ContextHandle) -> HandlerResult {
         _ => {},
     }
 
-    // Create the session with common data, but do not yet save it.
-    ctx.start_session(&client_id, &login_hint, &email_addr, &nonce);
+    // Create the session with common data, but do not yet save it. The
+    // response_type and code_challenge are carried on the session so that
+    // the bridge that eventually completes the flow knows whether to
+    // redirect with an id_token directly, or to mint an authorization
+    // code bound to this session's PKCE challenge.
+    ctx.start_session(&client_id, &login_hint, &email_addr, &nonce, &response_type, code_challenge.as_ref().map(String::as_str));
 
-    // Discover the authentication endpoints based on the email domain.
-    let f = webfinger::query(&ctx.app, &email_addr);
+    // Statically-configured providers take priority over webfinger
+    // discovery, so operators can wire up a first-class IdP (Google
+    // Workspace, GitLab, Keycloak, ...) for a domain without it
+    // publishing Portier/OIDC webfinger links of its own.
+    //
+    // Both branches below converge on the same `.or_else` email-fallback
+    // tail further down, so a misconfigured or unreachable static
+    // provider falls through to the email loop exactly like a failed or
+    // empty webfinger discovery would, rather than returning early and
+    // skipping that fallback.
+    let domain = email_addr.domain().to_owned();
+    let f: Box<Future<Item = Response, Error = BrokerError>> = if let Some(provider) = ctx.app.providers.get(&domain) {
+        // Routed through auth_with_links (as the single link in a
+        // one-element list) so this gets the same per-attempt timeout
+        // as a webfinger-discovered link.
+        let link = Link { rel: Relation::OidcIssuer, href: provider.issuer.clone() };
+        let credentials = Rc::new((provider.client_id.clone(), provider.client_secret.clone()));
+        drop(ctx);
+        auth_with_links(Rc::clone(ctx_handle), Rc::clone(&email_addr), Rc::new(vec![link]), 0, Some(credentials))
+    } else {
+        // Clone the `Rc<App>` out before dropping `ctx` (the outer
+        // `borrow_mut()`), so the discovery-cache lookup below — which
+        // re-borrows `ctx_handle` synchronously, in the same call stack —
+        // doesn't panic with "already mutably borrowed".
+        let app = Rc::clone(&ctx.app);
+        drop(ctx);
 
-    // Try to authenticate with the first provider.
-    // TODO: Queue discovery of links and process in order, with individual timeouts.
-    let ctx_handle2 = Rc::clone(ctx_handle);
-    let email_addr2 = Rc::clone(&email_addr);
-    let f = f.and_then(move |links| {
-        match links.first() {
-            // Portier and Google providers share an implementation
-            Some(link @ &Link { rel: Relation::OidcIssuer, .. })
-                | Some(link @ &Link { rel: Relation::Portier, .. })
-                | Some(link @ &Link { rel: Relation::Google, .. })
-                => bridges::oidc::auth(&ctx_handle2, &email_addr2, link),
-            _ => Box::new(future::err(BrokerError::ProviderCancelled)),
-        }
-    });
+        // Discover the authentication endpoints based on the email domain,
+        // reusing a cached result where we have a fresh one so that a login
+        // doesn't pay for a webfinger round-trip on every attempt.
+        let links_f: Box<Future<Item = Vec<Link>, Error = BrokerError>> = match cached_links(ctx_handle, &domain) {
+            Ok(Some(links)) => Box::new(future::ok(links)),
+            Ok(None) => {
+                let ctx_handle2 = Rc::clone(ctx_handle);
+                let domain2 = domain.clone();
+                Box::new(webfinger::query(&app, &email_addr).and_then(move |(links, ttl)| {
+                    // Best-effort: a cache write failure shouldn't fail the login.
+                    let _ = cache_links(&ctx_handle2, &domain2, &links, ttl.unwrap_or(DEFAULT_DISCOVERY_CACHE_TTL));
+                    future::ok(links)
+                }))
+            },
+            Err(e) => Box::new(future::err(e)),
+        };
 
-    // Apply a timeout to discovery.
-    let ctx_handle2 = Rc::clone(ctx_handle);
-    let email_addr2 = Rc::clone(&email_addr);
-    let f = Timeout::new(Duration::from_secs(5), &ctx.app.handle)
-        .expect("failed to create discovery timeout")
-        .select2(f)
-        .then(move |result| {
-            match result {
-                // Timeout resolved first.
-                Ok(Either::A((_, f))) => {
-                    // Continue the discovery future in the background.
-                    ctx_handle2.borrow().app.handle.spawn(
-                        f.map(|_| ()).map_err(|e| { e.log(); () }));
-                    Err(BrokerError::Provider(
-                        format!("discovery timed out for {}", email_addr2)))
-                },
-                Err(Either::A((e, _))) => {
-                    panic!("error in discovery timeout: {}", e)
-                },
-                // Discovery resolved first.
-                Ok(Either::B((v, _))) => {
-                    Ok(v)
-                },
-                Err(Either::B((e, _))) => {
-                    Err(e)
-                },
-            }
-        });
-
-    // Fall back to email loop authentication.
+        // Try each link in order, giving each provider its own timeout, and
+        // falling through to the next link when one times out or fails.
+        let ctx_handle2 = Rc::clone(ctx_handle);
+        let email_addr2 = Rc::clone(&email_addr);
+        let links_f = links_f.and_then(move |links| auth_with_links(ctx_handle2, email_addr2, Rc::new(links), 0, None));
+
+        // Apply an overall budget across all of discovery, on top of each
+        // individual provider's own timeout.
+        let app2 = Rc::clone(&app);
+        let email_addr2 = Rc::clone(&email_addr);
+        Box::new(Timeout::new(app.discovery_timeout, &app.handle)
+            .expect("failed to create discovery timeout")
+            .select2(links_f)
+            .then(move |result| {
+                match result {
+                    // Timeout resolved first.
+                    Ok(Either::A((_, f))) => {
+                        // Continue the discovery future in the background.
+                        app2.handle.spawn(
+                            f.map(|_| ()).map_err(|e| { e.log(); () }));
+                        Err(BrokerError::Provider(
+                            format!("discovery timed out for {}", email_addr2)))
+                    },
+                    Err(Either::A((e, _))) => {
+                        panic!("error in discovery timeout: {}", e)
+                    },
+                    // Discovery resolved first.
+                    Ok(Either::B((v, _))) => {
+                        Ok(v)
+                    },
+                    Err(Either::B((e, _))) => {
+                        Err(e)
+                    },
+                }
+            }))
+    };
+
+    // Fall back to email loop authentication, whichever branch above
+    // produced `f`.
     let ctx_handle2 = Rc::clone(ctx_handle);
+    let domain2 = domain.clone();
     let f = f.or_else(move |e| {
         e.log();
         match e {
-            BrokerError::Provider(_)
-                | BrokerError::ProviderCancelled
+            BrokerError::Provider(_) => {
+                // Discovery as a whole failed, e.g. it ran past the
+                // overall budget. Drop the cached discovery result (if
+                // any) so the next attempt re-discovers instead of
+                // wedging behind a stale cache. A single link's
+                // `Provider` failure is handled in `auth_with_links`.
+                let _ = invalidate_discovery_cache(&ctx_handle2, &domain2);
+                bridges::email::auth(&ctx_handle2, &email_addr)
+            },
+            BrokerError::ProviderCancelled
                 => bridges::email::auth(&ctx_handle2, &email_addr),
             _ => Box::new(future::err(e))
         }
@@ -227,3 +472,256 @@ pub fn auth(ctx_handle: &ContextHandle) -> HandlerResult {
 
     Box::new(f)
 }
+
+
+/// Request handler for the token endpoint, used to redeem an
+/// authorization code issued by the Authorization Code flow.
+///
+/// Confidential and native clients exchange the one-time `code` they
+/// received on the front-channel redirect for the id_token, proving
+/// possession of the original request via PKCE's `code_verifier`.
+pub fn token(ctx_handle: &ContextHandle) -> HandlerResult {
+    let ctx = ctx_handle.borrow();
+    let mut params = match ctx.method {
+        Method::Post => ctx.form_params(),
+        _ => unreachable!(),
+    };
+
+    if try_get_input_param!(params, "grant_type") != "authorization_code" {
+        return Box::new(future::err(BrokerError::Input(
+            "unsupported grant_type, only authorization_code is supported".to_owned())));
+    }
+
+    let code = try_get_input_param!(params, "code");
+    let client_id = try_get_input_param!(params, "client_id");
+    let redirect_uri = try_get_input_param!(params, "redirect_uri");
+    let code_verifier = try_get_input_param!(params, "code_verifier");
+
+    let auth_code = match take_auth_code(ctx_handle, &code) {
+        Ok(Some(auth_code)) => auth_code,
+        Ok(None) => return Box::new(future::err(BrokerError::Input(
+            "the authorization code is invalid, expired, or has already been used".to_owned()))),
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    if client_id != auth_code.client_id || redirect_uri != auth_code.redirect_uri {
+        return Box::new(future::err(BrokerError::Input(
+            "client_id or redirect_uri does not match the authorization request".to_owned())));
+    }
+
+    if !verify_code_challenge(&code_verifier, &auth_code.code_challenge) {
+        return Box::new(future::err(BrokerError::Input(
+            "code_verifier does not match the original code_challenge".to_owned())));
+    }
+
+    let obj = json!({
+        "token_type": "bearer",
+        "id_token": auth_code.id_token,
+    });
+    Box::new(json_response(&obj, Duration::from_secs(0)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{App, Templates};
+    use context::Context;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use store::Store;
+    use store_limits::Limit;
+    use tokio_core::reactor::Core;
+    use webfinger::Relation;
+
+    #[test]
+    fn verify_code_challenge_accepts_matching_pair() {
+        // RFC 7636 Appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_code_challenge(verifier, challenge));
+    }
+
+    #[test]
+    fn verify_code_challenge_rejects_mismatched_pair() {
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(!verify_code_challenge("some-other-verifier", challenge));
+    }
+
+    fn test_app(core: &Core) -> Rc<App> {
+        Rc::new(App {
+            public_url: "https://broker.example".to_owned(),
+            allowed_origins: None,
+            discovery_ttl: Duration::from_secs(1),
+            keys_ttl: Duration::from_secs(1),
+            keys: vec![],
+            store: Store::new(),
+            limit_per_email: Limit { count: 5, window: Duration::from_secs(1) },
+            handle: core.handle(),
+            templates: Templates { login_hint: mustache::Template::compile("").unwrap() },
+            providers: HashMap::new(),
+            discovery_timeout: Duration::from_secs(1),
+            provider_timeout: Duration::from_millis(50),
+        })
+    }
+
+    #[test]
+    fn auth_with_links_tries_every_link_in_order_then_gives_up() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+        let ctx_handle = Rc::new(RefCell::new(
+            Context::new(app, Method::Get, String::new(), String::new())));
+        let email_addr = Rc::new("user@example.com".parse::<EmailAddress>().unwrap());
+
+        // bridges::oidc::auth is a stub that always fails with
+        // ProviderCancelled, so every link here should be attempted in
+        // turn (falling through each one's timeout/failure) and the
+        // whole chain should bottom out at ProviderCancelled once the
+        // list is exhausted, rather than hanging or erroring early.
+        let links = Rc::new(vec![
+            Link { rel: Relation::OidcIssuer, href: "https://one.example".to_owned() },
+            Link { rel: Relation::Google, href: "https://two.example".to_owned() },
+        ]);
+
+        match core.run(auth_with_links(ctx_handle, email_addr, links, 0, None)) {
+            Err(BrokerError::ProviderCancelled) => {},
+            Err(e) => panic!("expected ProviderCancelled once every link is exhausted, got error: {}", e),
+            Ok(_) => panic!("expected ProviderCancelled once every link is exhausted, got Ok(_)"),
+        }
+    }
+
+    fn token_request(app: Rc<App>, body: &str) -> ContextHandle {
+        Rc::new(RefCell::new(Context::new(app, Method::Post, String::new(), body.to_owned())))
+    }
+
+    fn issue_test_auth_code(app: &Rc<App>, client_id: &str, redirect_uri: &str, code_challenge: &str) -> String {
+        let ctx_handle = Rc::new(RefCell::new(
+            Context::new(Rc::clone(app), Method::Get, String::new(), String::new())));
+        issue_auth_code(&ctx_handle, &AuthCode {
+            id_token: "signed-id-token".to_owned(),
+            client_id: client_id.to_owned(),
+            redirect_uri: redirect_uri.to_owned(),
+            code_challenge: code_challenge.to_owned(),
+        }).unwrap()
+    }
+
+    #[test]
+    fn token_redeems_a_valid_code_exactly_once() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+
+        // RFC 7636 Appendix B's verifier/challenge pair.
+        let code = issue_test_auth_code(
+            &app, "https://relier.example", "https://relier.example/callback",
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+
+        let body = ::url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", &code)
+            .append_pair("client_id", "https://relier.example")
+            .append_pair("redirect_uri", "https://relier.example/callback")
+            .append_pair("code_verifier", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")
+            .finish();
+
+        match core.run(token(&token_request(Rc::clone(&app), &body))) {
+            Ok(_) => {},
+            Err(e) => panic!("expected the first redemption to succeed, got error: {}", e),
+        }
+
+        // The code was consumed by the first request, so replaying the
+        // exact same request must fail even though it hasn't expired.
+        match core.run(token(&token_request(app, &body))) {
+            Err(BrokerError::Input(_)) => {},
+            Err(e) => panic!("expected replay to be rejected as invalid input, got error: {}", e),
+            Ok(_) => panic!("expected replay of an already-redeemed code to fail, got Ok(_)"),
+        }
+    }
+
+    #[test]
+    fn token_rejects_a_client_id_that_does_not_match_the_authorization_request() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+
+        let code = issue_test_auth_code(
+            &app, "https://relier.example", "https://relier.example/callback",
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+
+        let body = ::url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", &code)
+            .append_pair("client_id", "https://impostor.example")
+            .append_pair("redirect_uri", "https://relier.example/callback")
+            .append_pair("code_verifier", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")
+            .finish();
+
+        match core.run(token(&token_request(app, &body))) {
+            Err(BrokerError::Input(_)) => {},
+            Err(e) => panic!("expected a client_id mismatch to be rejected as invalid input, got error: {}", e),
+            Ok(_) => panic!("expected a client_id mismatch to fail, got Ok(_)"),
+        }
+    }
+
+    #[test]
+    fn token_rejects_a_code_verifier_that_does_not_match_the_code_challenge() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+
+        let code = issue_test_auth_code(
+            &app, "https://relier.example", "https://relier.example/callback",
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM");
+
+        let body = ::url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", &code)
+            .append_pair("client_id", "https://relier.example")
+            .append_pair("redirect_uri", "https://relier.example/callback")
+            .append_pair("code_verifier", "some-other-verifier")
+            .finish();
+
+        match core.run(token(&token_request(app, &body))) {
+            Err(BrokerError::Input(_)) => {},
+            Err(e) => panic!("expected a code_verifier mismatch to be rejected as invalid input, got error: {}", e),
+            Ok(_) => panic!("expected a code_verifier mismatch to fail, got Ok(_)"),
+        }
+    }
+
+    #[test]
+    fn token_rejects_an_unknown_code() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+
+        let body = ::url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "authorization_code")
+            .append_pair("code", "not-a-code-we-ever-issued")
+            .append_pair("client_id", "https://relier.example")
+            .append_pair("redirect_uri", "https://relier.example/callback")
+            .append_pair("code_verifier", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")
+            .finish();
+
+        match core.run(token(&token_request(app, &body))) {
+            Err(BrokerError::Input(_)) => {},
+            Err(e) => panic!("expected an unknown code to be rejected as invalid input, got error: {}", e),
+            Ok(_) => panic!("expected an unknown code to fail, got Ok(_)"),
+        }
+    }
+
+    #[test]
+    fn auth_with_links_fails_fast_on_an_empty_link_list() {
+        let mut core = Core::new().unwrap();
+        let app = test_app(&core);
+        let ctx_handle = Rc::new(RefCell::new(
+            Context::new(app, Method::Get, String::new(), String::new())));
+        let email_addr = Rc::new("user@example.com".parse::<EmailAddress>().unwrap());
+
+        // An empty links list (e.g. webfinger returned nothing relevant)
+        // should fail fast with ProviderCancelled, without needing a
+        // bridge call or a timeout at all.
+        let links = Rc::new(Vec::new());
+
+        match core.run(auth_with_links(ctx_handle, email_addr, links, 0, None)) {
+            Err(BrokerError::ProviderCancelled) => {},
+            Err(e) => panic!("expected ProviderCancelled for an empty link list, got error: {}", e),
+            Ok(_) => panic!("expected ProviderCancelled for an empty link list, got Ok(_)"),
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use context::Context;
+use error::BrokerError;
+use futures::future::{self, Future};
+use handlers::auth::{issue_auth_code, AuthCode};
+use hyper::header::ContentType;
+use hyper::server::Response;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use url::Url;
+
+pub type ContextHandle = Rc<RefCell<Context>>;
+pub type HandlerResult = Box<Future<Item = Response, Error = BrokerError>>;
+
+/// How the signed result should be delivered to the relier: appended to
+/// the redirect as a URL fragment, or via an auto-submitting HTML form
+/// (`response_mode=form_post`).
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseMode {
+    Fragment,
+    FormPost,
+}
+
+/// Everything needed to build the front-channel response, captured by
+/// `handlers::auth::auth()` once `client_id` and `redirect_uri` have
+/// been validated.
+pub struct ReturnParams {
+    pub redirect_uri: Url,
+    pub response_mode: ResponseMode,
+    pub response_errors: bool,
+    pub state: String,
+}
+
+/// Wraps `obj` as a JSON response, with `Cache-Control: max-age=ttl`.
+pub fn json_response(obj: &Value, ttl: Duration) -> HandlerResult {
+    let body = match ::serde_json::to_string(obj) {
+        Ok(body) => body,
+        Err(e) => return Box::new(future::err(BrokerError::Custom(format!("failed to serialize response: {}", e)))),
+    };
+    let res = Response::new()
+        .with_header(ContentType::json())
+        .with_header(::hyper::header::CacheControl(vec![
+            ::hyper::header::CacheDirective::MaxAge(ttl.as_secs() as u32),
+        ]))
+        .with_body(body);
+    Box::new(future::ok(res))
+}
+
+/// Builds the front-channel response for the session in progress on
+/// `ctx_handle`, once a bridge has confirmed the user's identity.
+///
+/// For `response_type=id_token`, `id_token` goes straight to the relier
+/// per `response_mode`. For `response_type=code`, this instead mints an
+/// authorization code bound to `id_token` and the session's PKCE
+/// challenge (see `handlers::auth::issue_auth_code`), and sends the
+/// relier that code; it is redeemed later at the token endpoint.
+pub fn finish_session(ctx_handle: &ContextHandle, id_token: String) -> HandlerResult {
+    let ctx = ctx_handle.borrow();
+
+    let session = match ctx.session {
+        Some(ref session) => session,
+        None => return Box::new(future::err(BrokerError::Custom(
+            "finish_session called with no session in progress".to_owned()))),
+    };
+    let return_params = match ctx.return_params {
+        Some(ref return_params) => return_params,
+        None => return Box::new(future::err(BrokerError::Custom(
+            "finish_session called with no return params set".to_owned()))),
+    };
+
+    let params = if session.response_type == "code" {
+        let code_challenge = match session.code_challenge {
+            Some(ref code_challenge) => code_challenge.clone(),
+            None => return Box::new(future::err(BrokerError::Custom(
+                "code flow session is missing its code_challenge".to_owned()))),
+        };
+        let auth_code = AuthCode {
+            id_token,
+            client_id: session.client_id.clone(),
+            redirect_uri: return_params.redirect_uri.as_str().to_owned(),
+            code_challenge,
+        };
+        let code = match issue_auth_code(ctx_handle, &auth_code) {
+            Ok(code) => code,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        vec![("code".to_owned(), code), ("state".to_owned(), return_params.state.clone())]
+    } else {
+        vec![("id_token".to_owned(), id_token), ("state".to_owned(), return_params.state.clone())]
+    };
+
+    build_redirect_response(return_params, params)
+}
+
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn build_redirect_response(return_params: &ReturnParams, params: Vec<(String, String)>) -> HandlerResult {
+    match return_params.response_mode {
+        ResponseMode::Fragment => {
+            let mut url = return_params.redirect_uri.clone();
+            let fragment = url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(&params)
+                .finish();
+            url.set_fragment(Some(&fragment));
+            let res = Response::new()
+                .with_status(::hyper::StatusCode::SeeOther)
+                .with_header(::hyper::header::Location::new(url.into_string()));
+            Box::new(future::ok(res))
+        },
+        ResponseMode::FormPost => {
+            let inputs = params.iter()
+                .map(|&(ref name, ref value)| format!(
+                    "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+                    escape_html(name), escape_html(value)))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let body = format!(
+                "<!DOCTYPE html><html><body onload=\"document.forms[0].submit()\">\
+                 <form method=\"post\" action=\"{}\">{}</form></body></html>",
+                return_params.redirect_uri, inputs);
+            let res = Response::new()
+                .with_header(ContentType::html())
+                .with_body(body);
+            Box::new(future::ok(res))
+        },
+    }
+}
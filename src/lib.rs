@@ -0,0 +1,43 @@
+extern crate base64;
+extern crate futures;
+extern crate hyper;
+extern crate mustache;
+extern crate rand;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate sha2;
+extern crate tokio_core;
+extern crate url;
+
+/// Pulls `name` out of `params`, returning a `BrokerError::Input` if
+/// it's missing (or a `$default` instead, when one is given).
+macro_rules! try_get_input_param {
+    ( $params:expr, $name:expr ) => {
+        match $params.iter().position(|&(ref k, _)| k == $name) {
+            Some(index) => $params.remove(index).1,
+            None => return Box::new(::futures::future::err(
+                ::error::BrokerError::Input(format!("missing request parameter {}", $name)))),
+        }
+    };
+    ( $params:expr, $name:expr, $default:expr ) => {
+        match $params.iter().position(|&(ref k, _)| k == $name) {
+            Some(index) => $params.remove(index).1,
+            None => $default,
+        }
+    };
+}
+
+pub mod bridges;
+pub mod config;
+pub mod context;
+pub mod crypto;
+pub mod email_address;
+pub mod error;
+pub mod handlers;
+pub mod http;
+pub mod store;
+pub mod store_limits;
+pub mod validation;
+pub mod webfinger;
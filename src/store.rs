@@ -0,0 +1,65 @@
+use error::BrokerError;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    value: String,
+    expires_at: Instant,
+}
+
+/// Key/value storage with per-entry TTLs, used for rate limiting,
+/// sessions, authorization codes and the discovery cache.
+///
+/// This is an in-process stand-in for the real backing store (normally
+/// Redis); callers only rely on the `get`/`store`/`take`/`remove`
+/// contract below, so swapping the backend doesn't touch call sites.
+pub struct Store {
+    entries: RefCell<HashMap<String, Entry>>,
+}
+
+impl Store {
+    pub fn new() -> Store {
+        Store { entries: RefCell::new(HashMap::new()) }
+    }
+
+    /// Fetches `key`, if present and not expired.
+    pub fn get(&self, key: &str) -> Result<Option<String>, BrokerError> {
+        let mut entries = self.entries.borrow_mut();
+        if let Some(entry) = entries.get(key) {
+            if entry.expires_at <= Instant::now() {
+                entries.remove(key);
+                return Ok(None);
+            }
+        }
+        Ok(entries.get(key).map(|entry| entry.value.clone()))
+    }
+
+    /// Stores `value` under `key`, expiring it after `ttl`.
+    pub fn store(&self, key: &str, value: &str, ttl: Duration) -> Result<(), BrokerError> {
+        self.entries.borrow_mut().insert(key.to_owned(), Entry {
+            value: value.to_owned(),
+            expires_at: Instant::now() + ttl,
+        });
+        Ok(())
+    }
+
+    /// Fetches and immediately removes `key`, so a value can only ever
+    /// be taken once.
+    pub fn take(&self, key: &str) -> Result<Option<String>, BrokerError> {
+        let entry = self.entries.borrow_mut().remove(key);
+        Ok(entry.and_then(|entry| {
+            if entry.expires_at > Instant::now() {
+                Some(entry.value)
+            } else {
+                None
+            }
+        }))
+    }
+
+    /// Removes `key`, if present.
+    pub fn remove(&self, key: &str) -> Result<(), BrokerError> {
+        self.entries.borrow_mut().remove(key);
+        Ok(())
+    }
+}
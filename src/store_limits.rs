@@ -0,0 +1,30 @@
+use error::BrokerError;
+use std::time::Duration;
+use store::Store;
+
+/// Counts attempts for `addr` in the current window, returning `false`
+/// once `limit` has been exceeded.
+///
+/// Resets implicitly: the counter entry expires after one window, so a
+/// client that stays quiet for a while gets a clean slate rather than
+/// being penalized forever for an old burst.
+pub fn addr_limiter(store: &Store, addr: &str, limit: &Limit) -> Result<bool, BrokerError> {
+    let key = format!("ratelimit:{}", addr);
+    let count = match store.get(&key)? {
+        Some(value) => value.parse::<u32>().unwrap_or(0),
+        None => 0,
+    };
+
+    if count >= limit.count {
+        return Ok(false);
+    }
+
+    store.store(&key, &(count + 1).to_string(), limit.window)?;
+    Ok(true)
+}
+
+/// A rate limit: at most `count` attempts per `window`.
+pub struct Limit {
+    pub count: u32,
+    pub window: Duration,
+}
